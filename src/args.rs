@@ -12,6 +12,7 @@ use tokio::{
 use url::Url;
 
 use crate::{
+    crawl::crawl,
     download::download,
     scan::scan,
     Error,
@@ -32,6 +33,7 @@ impl Args {
         match self.command {
             Command::Download(args) => download(self.global_args, args).await?,
             Command::Scan(args) => scan(self.global_args, args).await?,
+            Command::Crawl(args) => crawl(self.global_args, args).await?,
         }
 
         Ok(())
@@ -48,7 +50,13 @@ pub struct GlobalArgs {
 pub enum Command {
     #[structopt(alias = "d")]
     Download(DownloadArgs),
+    #[structopt(alias = "s")]
     Scan(ScanArgs),
+    /// Scan pages and download matching links in one pass; with
+    /// `--recursive`, also crawl same-origin links, like a `wget -r`
+    /// replacement.
+    #[structopt(alias = "c")]
+    Crawl(CrawlArgs),
 }
 
 #[derive(Debug, StructOpt)]
@@ -62,6 +70,29 @@ pub struct DownloadArgs {
     #[structopt(short, long, default_value = "1")]
     pub parallel: usize,
 
+    /// Number of times to retry a download after a failed or interrupted
+    /// transfer, with exponential backoff between attempts.
+    #[structopt(long, default_value = "5")]
+    pub retries: usize,
+
+    /// Maximum number of simultaneous downloads from a single host, on top
+    /// of the global `--parallel` limit. Keeps fire-dl from tripping
+    /// rate-limit or anti-DDoS protections when pointed at many URLs on the
+    /// same host.
+    #[structopt(long, default_value = "4")]
+    pub parallel_per_host: usize,
+
+    /// File with expected checksums, one per line: `<algo>:<hex>  <url>`.
+    /// Supported algorithms are `sha256` and `md5`. Downloads are verified
+    /// against the matching entry before being moved to their final path.
+    #[structopt(long)]
+    pub checksums: Option<PathBuf>,
+
+    /// After a successful run, write the checksums of all downloaded files
+    /// to this file, in the same format accepted by `--checksums`.
+    #[structopt(long)]
+    pub write_checksums: Option<PathBuf>,
+
     #[structopt(flatten)]
     pub urls: Urls,
 }
@@ -71,14 +102,69 @@ pub struct ScanArgs {
     #[structopt(short, long)]
     pub output: Option<PathBuf>,
 
+    #[structopt(flatten)]
+    pub filters: Filters,
+
+    #[structopt(short, long, default_value = "1")]
+    pub parallel: usize,
+
+    /// Maximum number of simultaneous requests to a single host, on top of
+    /// the global `--parallel` limit. Keeps fire-dl from tripping rate-limit
+    /// or anti-DDoS protections when pointed at many URLs on the same host.
+    #[structopt(long, default_value = "4")]
+    pub parallel_per_host: usize,
+
+    #[structopt(flatten)]
+    pub urls: Urls,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Filters {
+    /// Only consider links matching this pattern. May be given multiple
+    /// times; a link is kept if it matches any of them.
+    #[structopt(long)]
+    pub filter_url: Vec<Regex>,
+}
+
+impl Filters {
+    pub fn is_match(&self, url: &Url) -> bool {
+        let url_str = url.to_string();
+        self.filter_url.iter().any(|regex| regex.is_match(&url_str))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CrawlArgs {
     #[structopt(short, long)]
-    pub recursive: bool,
+    pub output: Option<PathBuf>,
 
+    /// Follow same-origin links found on crawled pages instead of only
+    /// scanning the given URLs.
     #[structopt(short, long)]
+    pub recursive: bool,
+
+    /// When crawling recursively, don't follow links above the starting
+    /// URL's path.
+    #[structopt(long)]
     pub no_parent: bool,
 
-    #[structopt(short, long)]
-    pub filter: Regex,
+    #[structopt(flatten)]
+    pub filters: Filters,
+
+    #[structopt(short, long, default_value = "1")]
+    pub parallel: usize,
+
+    /// Maximum number of simultaneous downloads from a single host.
+    #[structopt(long, default_value = "4")]
+    pub parallel_per_host: usize,
+
+    /// Number of times to retry a download after a failed or interrupted
+    /// transfer, with exponential backoff between attempts.
+    #[structopt(long, default_value = "5")]
+    pub retries: usize,
+
+    #[structopt(long)]
+    pub redownload_existing: bool,
 
     #[structopt(flatten)]
     pub urls: Urls,
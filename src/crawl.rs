@@ -0,0 +1,249 @@
+//! The `crawl` command: scan and download in a single pass, instead of
+//! piping `scan`'s output into `download` by hand.
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+
+use color_eyre::eyre::bail;
+use futures::StreamExt;
+use reqwest::Client;
+use tokio::sync::mpsc::{
+    unbounded_channel,
+    UnboundedSender,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use url::Url;
+
+use crate::{
+    args::{
+        CrawlArgs,
+        Filters,
+        GlobalArgs,
+    },
+    downloader::{
+        Downloader,
+        DownloaderRegistry,
+        HttpDownloader,
+    },
+    scan::fetch_links,
+    schedule::{
+        PendingDownload,
+        Progress,
+    },
+    utils::{
+        dedup_urls,
+        FileNameRegistry,
+        HostLimiter,
+    },
+    Error,
+};
+
+pub async fn crawl(global: GlobalArgs, args: CrawlArgs) -> Result<(), Error> {
+    let output = args.output.as_deref().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    if !output.exists() {
+        bail!("output path does not exist");
+    }
+    if !output.metadata()?.is_dir() {
+        bail!("output path is not a directory");
+    }
+
+    let redownload_existing = args.redownload_existing;
+    let recursive = args.recursive;
+    let no_parent = args.no_parent;
+    let parallel = args.parallel;
+
+    let roots = dedup_urls(args.urls.collect().await?).collect::<Vec<_>>();
+    let bounds = roots
+        .iter()
+        .map(|url| (origin_key(url), parent_dir(url)))
+        .collect::<Vec<_>>();
+
+    let client = Client::builder().user_agent(global.user_agent).build()?;
+    let host_limiter = HostLimiter::new(args.parallel_per_host);
+    let http_downloader: Arc<dyn Downloader> =
+        Arc::new(HttpDownloader::new(client.clone(), args.retries, host_limiter));
+
+    let mut registry = DownloaderRegistry::new();
+    registry.register("http", http_downloader.clone());
+    registry.register("https", http_downloader);
+
+    let file_names = FileNameRegistry::new();
+    let next_job_id = Arc::new(AtomicUsize::new(0));
+    // crawl doesn't support checksum manifests, so this never grows, but
+    // `PendingDownload`/`Job` need somewhere to report to
+    let checksum_records = Arc::new(Mutex::new(Vec::new()));
+    let progress = Progress::new(None);
+
+    // every URL we've already scanned or queued for download, so the crawl
+    // scans each one at most once and downloads each one at most once
+    let seen = Arc::new(Mutex::new(roots.iter().cloned().collect::<HashSet<_>>()));
+
+    let (scan_tx, scan_rx) = unbounded_channel();
+    let (download_tx, download_rx) = unbounded_channel();
+
+    let crawl = Crawl {
+        client,
+        filters: Arc::new(args.filters),
+        recursive,
+        no_parent,
+        bounds: Arc::new(bounds),
+        seen,
+        scan_tx: scan_tx.clone(),
+        download_tx: download_tx.clone(),
+    };
+
+    for url in roots {
+        scan_tx
+            .send(ScanJob {
+                url,
+                crawl: crawl.clone(),
+            })
+            .unwrap();
+    }
+
+    // we need to drop every extra sender, otherwise the receiver streams below
+    // will never end
+    drop(scan_tx);
+    drop(download_tx);
+    drop(crawl);
+
+    let scanning = UnboundedReceiverStream::new(scan_rx)
+        .map(|job| {
+            async move {
+                let span = tracing::info_span!("crawl-scan", url = %job.url);
+                let _guard = span.enter();
+                if let Err(error) = job.run().await {
+                    tracing::error!("{error}");
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .collect::<()>();
+
+    let downloading = UnboundedReceiverStream::new(download_rx)
+        .map(|url| {
+            let registry = registry.clone();
+            let output = output.clone();
+            let file_names = file_names.clone();
+            let next_job_id = next_job_id.clone();
+            let checksum_records = checksum_records.clone();
+            let progress = progress.clone();
+            async move {
+                let span = tracing::info_span!("crawl-download", url = %url);
+                let _guard = span.enter();
+
+                let Some(downloader) = registry.get(url.scheme()) else {
+                    tracing::error!(url = %url, "no downloader registered for scheme {:?}", url.scheme());
+                    return;
+                };
+
+                let pending = PendingDownload {
+                    id: next_job_id.fetch_add(1, Ordering::Relaxed),
+                    url,
+                    output_dir: output,
+                    downloader,
+                    redownload_existing,
+                    checksum: None,
+                    write_checksum: false,
+                    checksum_records,
+                };
+
+                match pending.prepare(&file_names).await {
+                    Ok(Some(job)) => job.run(progress).await,
+                    Ok(None) => {}
+                    Err(error) => tracing::error!("{error}"),
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .collect::<()>();
+
+    tokio::join!(scanning, downloading);
+
+    Ok(())
+}
+
+fn origin_key(url: &Url) -> String {
+    format!(
+        "{}://{}:{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+fn parent_dir(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(index) => path[..=index].to_owned(),
+        None => "/".to_owned(),
+    }
+}
+
+#[derive(Clone)]
+struct Crawl {
+    client: Client,
+    filters: Arc<Filters>,
+    recursive: bool,
+    no_parent: bool,
+    bounds: Arc<Vec<(String, String)>>,
+    seen: Arc<Mutex<HashSet<Url>>>,
+    scan_tx: UnboundedSender<ScanJob>,
+    download_tx: UnboundedSender<Url>,
+}
+
+impl Crawl {
+    /// Whether `url` is same-origin as one of the crawl's starting URLs and,
+    /// if `--no-parent` is set, at or below that URL's starting directory.
+    fn should_follow(&self, url: &Url) -> bool {
+        let origin = origin_key(url);
+        self.bounds
+            .iter()
+            .any(|(bound_origin, start_dir)| {
+                *bound_origin == origin && (!self.no_parent || url.path().starts_with(start_dir))
+            })
+    }
+}
+
+struct ScanJob {
+    url: Url,
+    crawl: Crawl,
+}
+
+impl ScanJob {
+    async fn run(self) -> Result<(), Error> {
+        let urls = fetch_links(&self.crawl.client, &self.url).await?;
+
+        for url in urls {
+            {
+                let mut seen = self.crawl.seen.lock().unwrap();
+                if !seen.insert(url.clone()) {
+                    continue;
+                }
+            }
+
+            if self.crawl.filters.is_match(&url) {
+                let _ = self.crawl.download_tx.send(url.clone());
+            }
+
+            if self.crawl.recursive && self.crawl.should_follow(&url) {
+                let _ = self.crawl.scan_tx.send(ScanJob {
+                    url,
+                    crawl: self.crawl.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
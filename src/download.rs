@@ -3,37 +3,45 @@ use std::{
         HashMap,
         HashSet,
     },
-    path::{
-        Path,
-        PathBuf,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
     },
-    sync::Arc,
 };
 
-use color_eyre::eyre::bail;
+use color_eyre::eyre::{
+    bail,
+    eyre,
+};
 pub use color_eyre::eyre::Error;
 use futures::StreamExt;
-use indicatif::{
-    MultiProgress,
-    ProgressBar,
-    ProgressStyle,
-};
-use reqwest::{
-    Client,
-    Response,
-};
-use tokio::{
-    fs::File,
-    io::{
-        AsyncWriteExt,
-        BufWriter,
-    },
+use reqwest::Client;
+use tokio::io::{
+    AsyncBufReadExt,
+    BufReader,
 };
 use url::Url;
 
-use crate::args::{
-    DownloadArgs,
-    GlobalArgs,
+use crate::{
+    args::{
+        DownloadArgs,
+        GlobalArgs,
+    },
+    downloader::{
+        Downloader,
+        DownloaderRegistry,
+        HttpDownloader,
+    },
+    schedule::{
+        Checksum,
+        PendingDownload,
+        Progress,
+    },
+    utils::{
+        FileNameRegistry,
+        HostLimiter,
+    },
 };
 
 pub async fn download(global: GlobalArgs, args: DownloadArgs) -> Result<(), Error> {
@@ -48,14 +56,34 @@ pub async fn download(global: GlobalArgs, args: DownloadArgs) -> Result<(), Erro
 
     let urls = args.urls.collect().await?;
 
+    let checksums = if let Some(path) = &args.checksums {
+        load_checksums(path).await?
+    }
+    else {
+        HashMap::new()
+    };
+    let write_checksums = args.write_checksums.is_some();
+    let checksum_records = Arc::new(Mutex::new(Vec::new()));
+
+    let client = Client::builder().user_agent(global.user_agent).build()?;
+    let host_limiter = HostLimiter::new(args.parallel_per_host);
+    let http_downloader: Arc<dyn Downloader> = Arc::new(HttpDownloader::new(
+        client,
+        args.retries,
+        host_limiter,
+    ));
+
+    let mut registry = DownloaderRegistry::new();
+    registry.register("http", http_downloader.clone());
+    registry.register("https", http_downloader);
+
+    let file_names = FileNameRegistry::new();
+
     let mut jobs = vec![];
     let mut id = 0;
 
-    let mut output_files = HashMap::new();
     let mut urls_seen = HashSet::new();
 
-    let client = Client::builder().user_agent(global.user_agent).build()?;
-
     for url in urls.into_iter() {
         // check if we already have a job for that url
         if urls_seen.contains(&url) {
@@ -65,37 +93,26 @@ pub async fn download(global: GlobalArgs, args: DownloadArgs) -> Result<(), Erro
             urls_seen.insert(url.clone());
         }
 
-        // todo: handle missing file name
-        let mut file_name = file_name_from_url(&url).unwrap().to_owned();
-
-        if let Some(suffix) = output_files.get_mut(&file_name) {
-            file_name = format!("{file_name}.{suffix}");
-            *suffix += 1;
-        }
-        else {
-            output_files.insert(file_name.clone(), 2);
-        }
+        let Some(downloader) = registry.get(url.scheme()) else {
+            tracing::error!(url = %url, "no downloader registered for scheme {:?}", url.scheme());
+            continue;
+        };
 
-        let mut unlink_existing = false;
-        let path = output.join(&file_name);
-        if path.exists() {
-            if args.redownload_existing {
-                tracing::info!(file_name, "file exists. redownloading.");
-                unlink_existing = true;
-            }
-            else {
-                tracing::info!(file_name, "file exists. skipping.");
-                continue;
-            }
-        }
+        let checksum = checksums.get(&url).cloned();
 
-        let job = Job {
-            client: client.clone(),
+        let pending = PendingDownload {
             id,
             url,
-            file_name,
-            path,
-            unlink_existing,
+            output_dir: output.to_path_buf(),
+            downloader,
+            redownload_existing: args.redownload_existing,
+            checksum,
+            write_checksum: write_checksums,
+            checksum_records: checksum_records.clone(),
+        };
+
+        let Some(job) = pending.prepare(&file_names).await? else {
+            continue;
         };
 
         id += 1;
@@ -104,11 +121,11 @@ pub async fn download(global: GlobalArgs, args: DownloadArgs) -> Result<(), Erro
 
     println!("downloading {} files", jobs.len());
 
-    let progress = Progress::new(jobs.len());
+    let progress = Progress::new(Some(jobs.len()));
 
     futures::stream::iter(&jobs)
         .map(|job| {
-            let span = tracing::info_span!("download", id = job.id);
+            let span = tracing::info_span!("download", id = job.id());
             let _guard = span.enter();
             let progress = progress.clone();
             async { job.clone().run(progress).await }
@@ -117,117 +134,49 @@ pub async fn download(global: GlobalArgs, args: DownloadArgs) -> Result<(), Erro
         .collect::<()>()
         .await;
 
+    if let Some(path) = &args.write_checksums {
+        write_checksums_file(path, &checksum_records.lock().unwrap()).await?;
+    }
+
     Ok(())
 }
 
-fn file_name_from_url(url: &Url) -> Option<&str> {
-    url.path_segments().and_then(|iter| iter.last())
-}
+/// Parses a checksum manifest of `<algo>:<hex>  <url>` lines, as produced by
+/// `--write-checksums` and consumed by `--checksums`.
+async fn load_checksums(path: &Path) -> Result<HashMap<Url, Checksum>, Error> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut lines = BufReader::new(file).lines();
+    let mut checksums = HashMap::new();
 
-#[derive(Clone)]
-struct Progress {
-    multi_progress: MultiProgress,
-    progress_style: Arc<ProgressStyle>,
-    spinner_style: Arc<ProgressStyle>,
-    num_jobs: usize,
-}
-
-impl Progress {
-    fn new(num_jobs: usize) -> Self {
-        let multi_progress = MultiProgress::new();
-
-        let progress_style = ProgressStyle::with_template(
-            "{prefix} {spinner:.green} {msg} [{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-        )
-            .unwrap()
-            .progress_chars("#>-");
-
-        let spinner_style = ProgressStyle::with_template(
-            "{prefix} {spinner:.green} {msg} [{elapsed_precise}] {bytes} ({bytes_per_sec})",
-        )
-        .unwrap();
-
-        Self {
-            multi_progress,
-            progress_style: Arc::new(progress_style),
-            spinner_style: Arc::new(spinner_style),
-            num_jobs,
-        }
-    }
-
-    fn add(self, id: usize, file_name: impl Into<String>, length: Option<u64>) -> ProgressBar {
-        let progress_bar;
-        if let Some(length) = length {
-            progress_bar = self.multi_progress.add(ProgressBar::new(length));
-            progress_bar.set_style(ProgressStyle::clone(&self.progress_style));
-        }
-        else {
-            progress_bar = ProgressBar::new_spinner();
-            progress_bar.set_style(ProgressStyle::clone(&self.spinner_style));
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-        progress_bar.set_message(file_name.into());
-        progress_bar.set_prefix(format!("[{}/{}]", id + 1, self.num_jobs));
-        progress_bar
-    }
-}
 
-#[derive(Clone)]
-struct Job {
-    client: Client,
-    id: usize,
-    url: Url,
-    file_name: String,
-    path: PathBuf,
-    unlink_existing: bool,
-}
-
-impl Job {
-    async fn run(self, progress: Progress) {
-        // todo: handle this error
-        let response = match self.client.get(self.url.clone()).send().await {
-            Ok(response) => response,
-            Err(error) => {
-                tracing::error!(file_name = self.file_name, "failed: {error}");
-                return;
-            }
+        let (algo_and_hex, url) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| eyre!("invalid checksum line: {line}"))?;
+        let (algo, hex) = algo_and_hex
+            .split_once(':')
+            .ok_or_else(|| eyre!("invalid checksum entry: {algo_and_hex}"))?;
+
+        let url: Url = url.trim().parse()?;
+        let checksum = Checksum {
+            algo: algo.parse()?,
+            hex: hex.to_lowercase(),
         };
-
-        let progress_bar = progress.add(self.id, &self.file_name, response.content_length());
-
-        if let Err(error) = self.download(response, &progress_bar).await {
-            progress_bar.abandon_with_message(format!("failed: {}: {error}", self.file_name));
-        }
-        else {
-            progress_bar.finish_and_clear();
-        }
+        checksums.insert(url, checksum);
     }
 
-    async fn download(
-        &self,
-        mut response: Response,
-        progress_bar: &ProgressBar,
-    ) -> Result<(), Error> {
-        let temp_path = self
-            .path
-            .parent()
-            .expect("output path has no parent directory")
-            .join(format!(".{}.part", self.file_name));
-
-        let file = File::create(&temp_path).await?;
-        let mut writer = BufWriter::new(file);
-
-        while let Some(chunk) = response.chunk().await? {
-            writer.write_all(&chunk).await?;
-            progress_bar.inc(chunk.len() as _);
-        }
-
-        writer.flush().await?;
-
-        if self.unlink_existing {
-            tokio::fs::remove_file(&self.path).await?;
-        }
-        tokio::fs::rename(temp_path, &self.path).await?;
+    Ok(checksums)
+}
 
-        Ok(())
+async fn write_checksums_file(path: &Path, records: &[(Url, Checksum)]) -> Result<(), Error> {
+    let mut manifest = String::new();
+    for (url, checksum) in records {
+        manifest.push_str(&format!("{}:{}  {url}\n", checksum.algo, checksum.hex));
     }
+    tokio::fs::write(path, manifest).await?;
+    Ok(())
 }
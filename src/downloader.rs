@@ -0,0 +1,320 @@
+//! Pluggable fetch backends.
+//!
+//! The scheduling code in `schedule.rs` doesn't know how to speak HTTP, FTP,
+//! or anything else — it only knows how to hand a [`DownloadInfo`] to
+//! whatever [`Downloader`] is registered for a URL's scheme and listen for
+//! [`DownloadStatus`] updates. This is what lets new backends (a local
+//! `file://` copier, say, or an FTP/S3 client) be added without touching the
+//! job scheduling, progress bars, or retry/checksum machinery.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use reqwest::{
+    header::{
+        self,
+        HeaderMap,
+    },
+    Client,
+    StatusCode,
+};
+use thiserror::Error;
+use tokio::{
+    fs::OpenOptions,
+    io::{
+        AsyncWriteExt,
+        BufWriter,
+    },
+};
+use url::Url;
+
+use crate::utils::{
+    percent_decode,
+    sanitize_file_name,
+    ChecksumAlgo,
+    FileNameRegistry,
+    Hasher,
+    HostLimiter,
+};
+
+/// Everything a [`Downloader`] needs to know to fetch a single file.
+///
+/// `provisional_file_name` is only a best-effort guess made before the
+/// request went out (used for upfront collision checks); a backend that
+/// learns a better name once headers arrive (e.g. from
+/// `Content-Disposition`) is free to claim a different one from
+/// `file_names` and save to that path instead.
+#[derive(Clone)]
+pub struct DownloadInfo {
+    pub url: Url,
+    pub output_dir: PathBuf,
+    pub provisional_file_name: String,
+    pub file_names: FileNameRegistry,
+
+    /// If set, the backend hashes the downloaded bytes with this algorithm
+    /// as they're streamed to disk, rather than re-reading the whole file
+    /// afterwards. The digest is reported via
+    /// [`DownloadStatus::Checksum`] once streaming finishes.
+    pub checksum_algo: Option<ChecksumAlgo>,
+
+    /// If set (requires `checksum_algo` to also be set), the computed
+    /// digest is compared against this expected hex digest before the
+    /// `.part` file is renamed into place; a mismatch fails the download
+    /// with [`DownloadError::ChecksumMismatch`] and the `.part` file is
+    /// removed.
+    pub expected_checksum: Option<String>,
+}
+
+/// A status update emitted by a [`Downloader`] as it works. Consumers (e.g.
+/// the progress bar wiring in `schedule.rs`) react to these instead of
+/// reaching into backend-specific state.
+#[derive(Debug, Clone)]
+pub enum DownloadStatus {
+    Started { total_len: Option<u64> },
+    Progress { bytes_written: u64 },
+    /// The hex digest of the downloaded file, computed as requested via
+    /// `DownloadInfo::checksum_algo`. Emitted once, after the transfer
+    /// completes and before `Finished`.
+    Checksum(String),
+    Finished,
+    Failed,
+}
+
+pub type StatusCallback<'a> = dyn Fn(DownloadStatus) + Send + Sync + 'a;
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("no downloader registered for scheme {0:?}")]
+    UnsupportedScheme(String),
+
+    #[error("checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+/// A backend capable of fetching the resource described by a [`DownloadInfo`].
+/// Returns the path the file was actually saved to, which may differ from
+/// `info.output_dir.join(info.provisional_file_name)` if a better name was
+/// discovered along the way.
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    async fn download(
+        &self,
+        info: DownloadInfo,
+        status: &StatusCallback<'_>,
+    ) -> Result<PathBuf, DownloadError>;
+}
+
+/// Maps URL schemes (`"http"`, `"file"`, ...) to the backend that handles
+/// them.
+#[derive(Clone, Default)]
+pub struct DownloaderRegistry {
+    backends: HashMap<String, Arc<dyn Downloader>>,
+}
+
+impl DownloaderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, scheme: impl Into<String>, downloader: Arc<dyn Downloader>) {
+        self.backends.insert(scheme.into(), downloader);
+    }
+
+    pub fn get(&self, scheme: &str) -> Option<Arc<dyn Downloader>> {
+        self.backends.get(scheme).cloned()
+    }
+}
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The default backend for `http`/`https` URLs: streams the response body
+/// to a `.part` file, resuming via `Range` and retrying with exponential
+/// backoff on failure.
+pub struct HttpDownloader {
+    client: Client,
+    retries: usize,
+    host_limiter: HostLimiter,
+}
+
+impl HttpDownloader {
+    pub fn new(client: Client, retries: usize, host_limiter: HostLimiter) -> Self {
+        Self {
+            client,
+            retries,
+            host_limiter,
+        }
+    }
+
+    /// Runs one fetch attempt. The `.part` file is always keyed off
+    /// `info.provisional_file_name`, so retries resume the same file
+    /// regardless of what the final name turns out to be. On the first
+    /// attempt to see response headers, resolves `resolved_path` from the
+    /// `Content-Disposition` header of the real `GET` response (falling
+    /// back to the provisional name); later attempts reuse that resolution
+    /// instead of re-deriving it.
+    async fn attempt(
+        &self,
+        info: &DownloadInfo,
+        resolved_path: &mut Option<PathBuf>,
+        status: &StatusCallback<'_>,
+    ) -> Result<(), DownloadError> {
+        let temp_path = info
+            .output_dir
+            .join(format!(".{}.part", info.provisional_file_name));
+
+        let resume_from = tokio::fs::metadata(&temp_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        let mut request = self.client.get(info.url.clone());
+        if resume_from > 0 {
+            request = request.header(header::RANGE, format!("bytes={resume_from}-"));
+        }
+
+        let mut response = request.send().await?.error_for_status()?;
+
+        // the server may ignore our Range header and send the whole file back, in
+        // which case we have to start over
+        let (append, start_at) = match response.status() {
+            StatusCode::PARTIAL_CONTENT => (true, resume_from),
+            _ => (false, 0),
+        };
+
+        if resolved_path.is_none() {
+            let file_name = match file_name_from_content_disposition(response.headers()) {
+                Some(name) if name != info.provisional_file_name => info.file_names.claim(&name),
+                _ => info.provisional_file_name.clone(),
+            };
+            *resolved_path = Some(info.output_dir.join(file_name));
+        }
+
+        let total_len = response.content_length().map(|len| len + start_at);
+        status(DownloadStatus::Started { total_len });
+        status(DownloadStatus::Progress {
+            bytes_written: start_at,
+        });
+
+        // if we're appending to bytes already on disk from a previous attempt,
+        // the hasher needs to start from those bytes too
+        let mut hasher = info.checksum_algo.map(Hasher::new);
+        if append {
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&tokio::fs::read(&temp_path).await?);
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&temp_path)
+            .await?;
+        let mut writer = BufWriter::new(file);
+        let mut written = start_at;
+
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await?;
+            writer.flush().await?;
+            written += chunk.len() as u64;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&chunk);
+            }
+            status(DownloadStatus::Progress {
+                bytes_written: written,
+            });
+        }
+
+        if let Some(hasher) = hasher {
+            let digest = hasher.finalize_hex();
+
+            if let Some(expected) = &info.expected_checksum {
+                if digest != *expected {
+                    tokio::fs::remove_file(&temp_path).await?;
+                    return Err(DownloadError::ChecksumMismatch {
+                        expected: expected.clone(),
+                        actual: digest,
+                    });
+                }
+            }
+
+            status(DownloadStatus::Checksum(digest));
+        }
+
+        tokio::fs::rename(temp_path, resolved_path.as_ref().unwrap()).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Downloader for HttpDownloader {
+    async fn download(
+        &self,
+        info: DownloadInfo,
+        status: &StatusCallback<'_>,
+    ) -> Result<PathBuf, DownloadError> {
+        let host = info.url.host_str().unwrap_or_default().to_owned();
+        let _host_permit = self.host_limiter.acquire(&host).await;
+
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut resolved_path = None;
+
+        for attempt in 0..=self.retries {
+            match self.attempt(&info, &mut resolved_path, status).await {
+                Ok(()) => {
+                    status(DownloadStatus::Finished);
+                    return Ok(resolved_path.expect("resolved during a successful attempt"));
+                }
+                Err(error) => {
+                    if attempt == self.retries {
+                        status(DownloadStatus::Failed);
+                        return Err(error);
+                    }
+
+                    tracing::warn!(
+                        url = %info.url,
+                        attempt,
+                        "download failed, retrying in {delay:?}: {error}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+
+        unreachable!("loop above always returns")
+    }
+}
+
+/// Extracts a sanitized file name from a `Content-Disposition` header,
+/// preferring the RFC 6266 `filename*=` extended form over plain
+/// `filename=`.
+fn file_name_from_content_disposition(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::CONTENT_DISPOSITION)?.to_str().ok()?;
+    let parts = || value.split(';').map(str::trim);
+
+    let extended = parts().find_map(|part| part.strip_prefix("filename*="));
+    if let Some(extended) = extended {
+        let (_charset_and_lang, encoded) = extended.split_once("''")?;
+        let name = percent_decode(encoded.trim_matches('"'));
+        return Some(sanitize_file_name(&name)).filter(|name| !name.is_empty());
+    }
+
+    let plain = parts().find_map(|part| part.strip_prefix("filename="))?;
+    let name = sanitize_file_name(plain.trim_matches('"'));
+    Some(name).filter(|name| !name.is_empty())
+}
@@ -1,6 +1,9 @@
 mod args;
+mod crawl;
 mod download;
+mod downloader;
 mod scan;
+mod schedule;
 mod utils;
 
 pub use color_eyre::eyre::Error;
@@ -1,10 +1,6 @@
-use std::{
-    path::PathBuf,
-    sync::Arc,
-};
+use std::sync::Arc;
 
 use futures::StreamExt;
-use regex::Regex;
 use reqwest::{
     header,
     Client,
@@ -14,7 +10,6 @@ use soup::{
     QueryBuilderExt,
     Soup,
 };
-use structopt::StructOpt;
 use tokio::sync::mpsc::{
     unbounded_channel,
     UnboundedSender,
@@ -24,34 +19,25 @@ use url::Url;
 
 use crate::{
     args::{
-        Globals,
-        Urls,
+        Filters,
+        GlobalArgs,
+        ScanArgs,
     },
+    utils::HostLimiter,
     Error,
 };
 
-#[derive(Debug, StructOpt)]
-pub struct ScanArgs {
-    #[structopt(short, long)]
-    pub output: Option<PathBuf>,
-
-    #[structopt(flatten)]
-    pub filters: Filters,
-
-    #[structopt(short, long, default_value = "1")]
-    pub parallel: usize,
+pub async fn scan(global: GlobalArgs, args: ScanArgs) -> Result<(), Error> {
+    let client = Client::builder().user_agent(global.user_agent).build()?;
+    let host_limiter = HostLimiter::new(args.parallel_per_host);
 
-    #[structopt(flatten)]
-    pub urls: Urls,
-}
-
-pub async fn scan(globals: Globals, args: ScanArgs) -> Result<(), Error> {
     let (job_tx, job_rx) = unbounded_channel();
     let (output_tx, mut output_rx) = unbounded_channel();
 
     let scan = Scan {
         filters: Arc::new(args.filters),
-        client: globals.client.clone(),
+        client,
+        host_limiter,
         output_tx,
     };
 
@@ -90,23 +76,11 @@ pub async fn scan(globals: Globals, args: ScanArgs) -> Result<(), Error> {
     Ok(())
 }
 
-#[derive(Debug, StructOpt)]
-pub struct Filters {
-    #[structopt(long)]
-    filter_url: Vec<Regex>,
-}
-
-impl Filters {
-    pub fn is_match(&self, url: &Url) -> bool {
-        let url_str = url.to_string();
-        self.filter_url.iter().any(|regex| regex.is_match(&url_str))
-    }
-}
-
 #[derive(Clone)]
 struct Scan {
     filters: Arc<Filters>,
     client: Client,
+    host_limiter: HostLimiter,
     output_tx: UnboundedSender<Url>,
 }
 
@@ -117,23 +91,10 @@ struct Job {
 
 impl Job {
     async fn run(self) -> Result<(), Error> {
-        let response = self.scan.client.get(self.url.clone()).send().await?;
+        let host = self.url.host_str().unwrap_or_default().to_owned();
+        let _host_permit = self.scan.host_limiter.acquire(&host).await;
 
-        let headers = response.headers();
-        let content_type = headers.get(header::CONTENT_TYPE);
-
-        let urls = if let Some(content_type) = content_type {
-            match content_type.to_str()? {
-                "text/html" => {
-                    let html = response.text().await?;
-                    scan_html(&html, &self.url)
-                }
-                _ => vec![],
-            }
-        }
-        else {
-            vec![]
-        };
+        let urls = fetch_links(&self.scan.client, &self.url).await?;
 
         for url in urls {
             if self.scan.filters.is_match(&url) {
@@ -145,6 +106,31 @@ impl Job {
     }
 }
 
+/// Fetches `url` and, if it's an HTML page, returns the links found on it
+/// (resolved against `url`). Shared by the plain `scan` command and the
+/// `crawl` command's recursive fetch loop.
+pub(crate) async fn fetch_links(client: &Client, url: &Url) -> Result<Vec<Url>, Error> {
+    let response = client.get(url.clone()).send().await?;
+
+    let headers = response.headers();
+    let content_type = headers.get(header::CONTENT_TYPE);
+
+    let urls = if let Some(content_type) = content_type {
+        match content_type.to_str()? {
+            "text/html" => {
+                let html = response.text().await?;
+                scan_html(&html, url)
+            }
+            _ => vec![],
+        }
+    }
+    else {
+        vec![]
+    };
+
+    Ok(urls)
+}
+
 fn scan_html(html: &str, base_url: &Url) -> Vec<Url> {
     let soup = Soup::new(html);
     let mut urls = vec![];
@@ -0,0 +1,269 @@
+//! Shared job scheduling: claim a name, decide whether to skip, redownload,
+//! or verify an existing file, dispatch through a [`Downloader`], and report
+//! progress. Used by both `download()` and `crawl()` so they don't maintain
+//! two independently-evolving copies of this logic.
+
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use indicatif::{
+    MultiProgress,
+    ProgressBar,
+    ProgressStyle,
+};
+use sha2::Digest as _;
+use url::Url;
+
+use crate::{
+    downloader::{
+        DownloadInfo,
+        DownloadStatus,
+        Downloader,
+    },
+    utils::{
+        hex_encode,
+        percent_decode,
+        sanitize_file_name,
+        ChecksumAlgo,
+        FileNameRegistry,
+    },
+    Error,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Checksum {
+    pub(crate) algo: ChecksumAlgo,
+    pub(crate) hex: String,
+}
+
+/// Derives a file name for `url` before the request is made: the last path
+/// segment, percent-decoded and sanitized, or (if the URL has no usable
+/// segment, e.g. `https://host/` or a query-only URL) a name synthesized
+/// from the host and a hash of the URL.
+pub(crate) fn derive_file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(percent_decode)
+        .map(|name| sanitize_file_name(&name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| synthesize_file_name(url))
+}
+
+fn synthesize_file_name(url: &Url) -> String {
+    let host = url.host_str().unwrap_or("download");
+    let hash = hex_encode(&sha2::Sha256::digest(url.as_str().as_bytes()));
+    format!("{host}-{}", &hash[..16])
+}
+
+/// Hashes a file already on disk, e.g. to check a pre-existing output
+/// against a checksum manifest before deciding whether to redownload it.
+/// The backend hashes newly-downloaded bytes as they're streamed instead of
+/// re-reading them afterwards; see `downloader::HttpDownloader::attempt`.
+pub(crate) async fn hash_file(path: &Path, algo: ChecksumAlgo) -> Result<String, Error> {
+    let data = tokio::fs::read(path).await?;
+    Ok(match algo {
+        ChecksumAlgo::Sha256 => hex_encode(&sha2::Sha256::digest(&data)),
+        ChecksumAlgo::Md5 => hex_encode(&<md5::Md5 as md5::Digest>::digest(&data)),
+    })
+}
+
+/// Everything needed to claim a file name, check for a pre-existing output,
+/// and turn the result into a runnable [`Job`] — the common first half of
+/// scheduling a download that both `download()` and `crawl()` go through.
+pub(crate) struct PendingDownload {
+    pub(crate) id: usize,
+    pub(crate) url: Url,
+    pub(crate) output_dir: PathBuf,
+    pub(crate) downloader: Arc<dyn Downloader>,
+    pub(crate) redownload_existing: bool,
+    pub(crate) checksum: Option<Checksum>,
+    pub(crate) write_checksum: bool,
+    pub(crate) checksum_records: Arc<Mutex<Vec<(Url, Checksum)>>>,
+}
+
+impl PendingDownload {
+    /// Claims a name for `self.url`, checks the output path for a
+    /// pre-existing file (skipping, redownloading, or verifying it against
+    /// `self.checksum` as appropriate), and builds the [`Job`] that runs it.
+    /// Returns `None` if the file should be skipped.
+    pub(crate) async fn prepare(self, file_names: &FileNameRegistry) -> Result<Option<Job>, Error> {
+        let provisional_file_name = file_names.claim(&derive_file_name(&self.url));
+
+        let mut unlink_existing = false;
+        let path = self.output_dir.join(&provisional_file_name);
+        if path.exists() {
+            if let Some(checksum) = &self.checksum {
+                if hash_file(&path, checksum.algo).await? == checksum.hex {
+                    tracing::info!(file_name = provisional_file_name, "file exists and matches checksum. skipping.");
+                    return Ok(None);
+                }
+                tracing::info!(file_name = provisional_file_name, "file exists but checksum doesn't match. redownloading.");
+                unlink_existing = true;
+            }
+            else if self.redownload_existing {
+                tracing::info!(file_name = provisional_file_name, "file exists. redownloading.");
+                unlink_existing = true;
+            }
+            else {
+                tracing::info!(file_name = provisional_file_name, "file exists. skipping.");
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(Job {
+            id: self.id,
+            url: self.url,
+            output_dir: self.output_dir,
+            provisional_file_name,
+            unlink_existing,
+            downloader: self.downloader,
+            file_names: file_names.clone(),
+            checksum: self.checksum,
+            write_checksum: self.write_checksum,
+            checksum_records: self.checksum_records,
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Progress {
+    multi_progress: MultiProgress,
+    progress_style: Arc<ProgressStyle>,
+    spinner_style: Arc<ProgressStyle>,
+    num_jobs: Option<usize>,
+}
+
+impl Progress {
+    /// `num_jobs` is `None` when the total isn't known upfront, e.g. `crawl`
+    /// discovers download candidates as it goes instead of collecting them
+    /// all before starting.
+    pub(crate) fn new(num_jobs: Option<usize>) -> Self {
+        let multi_progress = MultiProgress::new();
+
+        let progress_style = ProgressStyle::with_template(
+            "{prefix} {spinner:.green} {msg} [{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+        )
+            .unwrap()
+            .progress_chars("#>-");
+
+        let spinner_style = ProgressStyle::with_template(
+            "{prefix} {spinner:.green} {msg} [{elapsed_precise}] {bytes} ({bytes_per_sec})",
+        )
+        .unwrap();
+
+        Self {
+            multi_progress,
+            progress_style: Arc::new(progress_style),
+            spinner_style: Arc::new(spinner_style),
+            num_jobs,
+        }
+    }
+
+    fn add(&self, id: usize, file_name: impl Into<String>) -> ProgressBar {
+        let progress_bar = ProgressBar::new_spinner();
+        progress_bar.set_style(ProgressStyle::clone(&self.spinner_style));
+        progress_bar.set_message(file_name.into());
+        progress_bar.set_prefix(match self.num_jobs {
+            Some(num_jobs) => format!("[{}/{}]", id + 1, num_jobs),
+            None => format!("[{}]", id + 1),
+        });
+        self.multi_progress.add(progress_bar)
+    }
+
+    fn set_length(&self, progress_bar: &ProgressBar, length: u64) {
+        progress_bar.set_length(length);
+        progress_bar.set_style(ProgressStyle::clone(&self.progress_style));
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Job {
+    id: usize,
+    url: Url,
+    output_dir: PathBuf,
+    provisional_file_name: String,
+    unlink_existing: bool,
+    downloader: Arc<dyn Downloader>,
+    file_names: FileNameRegistry,
+    checksum: Option<Checksum>,
+    write_checksum: bool,
+    checksum_records: Arc<Mutex<Vec<(Url, Checksum)>>>,
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) async fn run(self, progress: Progress) {
+        if self.unlink_existing {
+            let path = self.output_dir.join(&self.provisional_file_name);
+            if let Err(error) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(
+                    file_name = self.provisional_file_name,
+                    "failed to remove existing file: {error}"
+                );
+            }
+        }
+
+        let progress_bar = progress.add(self.id, &self.provisional_file_name);
+
+        // hash while streaming if we need to verify against a known checksum, or to
+        // record one for --write-checksums (defaulting to sha256 in that case)
+        let checksum_algo = self
+            .checksum
+            .as_ref()
+            .map(|checksum| checksum.algo)
+            .or(self.write_checksum.then_some(ChecksumAlgo::Sha256));
+
+        let info = DownloadInfo {
+            url: self.url.clone(),
+            output_dir: self.output_dir.clone(),
+            provisional_file_name: self.provisional_file_name.clone(),
+            file_names: self.file_names.clone(),
+            checksum_algo,
+            expected_checksum: self.checksum.as_ref().map(|checksum| checksum.hex.clone()),
+        };
+
+        let status = |update: DownloadStatus| match update {
+            DownloadStatus::Started { total_len: Some(len) } => progress.set_length(&progress_bar, len),
+            DownloadStatus::Started { total_len: None } => {}
+            DownloadStatus::Progress { bytes_written } => progress_bar.set_position(bytes_written),
+            DownloadStatus::Checksum(digest) => {
+                if self.write_checksum {
+                    if let Some(algo) = checksum_algo {
+                        self.checksum_records
+                            .lock()
+                            .unwrap()
+                            .push((self.url.clone(), Checksum { algo, hex: digest }));
+                    }
+                }
+            }
+            DownloadStatus::Finished | DownloadStatus::Failed => {}
+        };
+
+        let path = match self.downloader.download(info, &status).await {
+            Ok(path) => path,
+            Err(error) => {
+                progress_bar.abandon_with_message(format!(
+                    "failed: {}: {error}",
+                    self.provisional_file_name
+                ));
+                return;
+            }
+        };
+        if let Some(file_name) = path.file_name() {
+            progress_bar.set_message(file_name.to_string_lossy().into_owned());
+        }
+
+        progress_bar.finish_and_clear();
+    }
+}
@@ -1,5 +1,26 @@
-use std::collections::HashSet;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
 
+use color_eyre::eyre::{
+    eyre,
+    Error,
+};
+use percent_encoding::percent_decode_str;
+use sha2::Digest as _;
+use tokio::sync::{
+    OwnedSemaphorePermit,
+    Semaphore,
+};
 use url::Url;
 
 pub fn dedup_urls(input: impl IntoIterator<Item = Url>) -> impl Iterator<Item = Url> {
@@ -15,3 +36,156 @@ pub fn dedup_urls(input: impl IntoIterator<Item = Url>) -> impl Iterator<Item =
         }
     })
 }
+
+/// Encodes `bytes` as a lowercase hex string.
+pub fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:02x}").expect("writing to a String never fails");
+    }
+    hex
+}
+
+/// Percent-decodes `s` (e.g. turning `%20` into a space), replacing any
+/// invalid UTF-8 with the replacement character.
+pub fn percent_decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+/// Neutralizes path separators, control characters, and leading dots, so
+/// `name` is safe to use as a single path component.
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_control() || c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+
+    sanitized.trim().trim_start_matches('.').to_owned()
+}
+
+/// Hands out file names from a shared namespace, so concurrent jobs that
+/// land on the same name (from the URL, a redirect, or a `Content-Disposition`
+/// header) don't clobber each other's output. The first caller to claim a
+/// name gets it unsuffixed; later callers get `<name>.2`, `<name>.3`, ...
+#[derive(Clone, Default)]
+pub struct FileNameRegistry {
+    claimed: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl FileNameRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn claim(&self, name: &str) -> String {
+        let mut claimed = self.claimed.lock().unwrap();
+        match claimed.get_mut(name) {
+            Some(next_suffix) => {
+                let suffixed = format!("{name}.{next_suffix}");
+                *next_suffix += 1;
+                suffixed
+            }
+            None => {
+                claimed.insert(name.to_owned(), 2);
+                name.to_owned()
+            }
+        }
+    }
+}
+
+/// Hands out per-host semaphore permits, so callers can cap how many jobs
+/// run concurrently against a single host independently of any global
+/// concurrency limit.
+#[derive(Clone)]
+pub struct HostLimiter {
+    max_per_host: usize,
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl HostLimiter {
+    pub fn new(max_per_host: usize) -> Self {
+        Self {
+            max_per_host,
+            semaphores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Waits for a free slot for `host` and returns a permit that releases
+    /// the slot again when dropped.
+    pub async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+            semaphores
+                .entry(host.to_owned())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+                .clone()
+        };
+
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed")
+    }
+}
+
+/// A supported checksum algorithm, as named in a checksum manifest
+/// (`<algo>:<hex>  <url>`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgo {
+    Sha256,
+    Md5,
+}
+
+impl FromStr for ChecksumAlgo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "md5" => Ok(Self::Md5),
+            _ => Err(eyre!("unsupported checksum algorithm: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "sha256"),
+            Self::Md5 => write!(f, "md5"),
+        }
+    }
+}
+
+/// Feeds chunks into the hasher matching a [`ChecksumAlgo`] incrementally,
+/// so callers can hash data as it's streamed rather than re-reading a whole
+/// file afterwards.
+pub enum Hasher {
+    Sha256(sha2::Sha256),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    pub fn new(algo: ChecksumAlgo) -> Self {
+        match algo {
+            ChecksumAlgo::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            ChecksumAlgo::Md5 => Self::Md5(md5::Md5::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Md5(hasher) => md5::Digest::update(hasher, data),
+        }
+    }
+
+    pub fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(hasher) => hex_encode(&hasher.finalize()),
+            Self::Md5(hasher) => hex_encode(&md5::Digest::finalize(hasher)),
+        }
+    }
+}